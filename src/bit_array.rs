@@ -8,19 +8,40 @@ pub struct BitArray {
 
 impl BitArray {
     pub fn new(size_in_bits: usize) -> BitArray {
-        // Ensure we allocate enough bytes by rounding up to the nearest multiple of 8.
-        let size_in_bytes = if size_in_bits % 8 == 0 {
-            size_in_bits / 8
-        } else {
-            (size_in_bits + 8 - size_in_bits % 8) / 8
-        };
-
         BitArray {
-            array: vec![0; size_in_bytes],
+            array: vec![0; size_in_bytes(size_in_bits)],
             size_in_bits,
         }
     }
 
+    /// Reconstruct a bit array previously serialized with [`BitArray::as_bytes`].
+    ///
+    /// Returns an error if `bytes` is not exactly the number of bytes `size_in_bits` requires, or
+    /// if `size_in_bits` is so large that the required number of bytes can't even be computed
+    /// without overflowing. `size_in_bits` is untrusted input here (it comes straight off the
+    /// wire), so this must reject rather than panic or wrap.
+    pub(crate) fn from_bytes(
+        size_in_bits: usize,
+        bytes: &[u8],
+    ) -> Result<BitArray, crate::FromBytesError> {
+        let expected_len =
+            checked_size_in_bytes(size_in_bits).ok_or(crate::FromBytesError::Truncated)?;
+
+        if bytes.len() != expected_len {
+            return Err(crate::FromBytesError::Truncated);
+        }
+
+        Ok(BitArray {
+            array: bytes.to_vec(),
+            size_in_bits,
+        })
+    }
+
+    /// The raw bytes backing this bit array, in the format expected by [`BitArray::from_bytes`].
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.array
+    }
+
     /// Set a bit to 1 based on the output of a hash function.
     pub fn set_bit_from_u64(&mut self, i: u64) {
         self.set_bit(self.bit_index_from_u64(i))
@@ -40,11 +61,64 @@ impl BitArray {
     }
 
     fn bit_index_from_u64(&self, i: u64) -> usize {
-        // The max value of f64 is always bigger than the max usize and u64, so the conversion of
-        // the integers to floats are safe. The final value is guaranteed to be less than the array
-        // size in bits, which is a usize, so it is safe to convert back to a usize.
-        ((i as f64 / u64::MAX as f64) * (self.size_in_bits - 1) as f64) as usize
+        index_from_u64(i, self.size_in_bits)
+    }
+
+    pub(crate) fn size_in_bits(&self) -> usize {
+        self.size_in_bits
+    }
+
+    /// Set every bit that is set in `other` in `self` as well, i.e. a bitwise OR.
+    ///
+    /// The caller must ensure both bit arrays have the same `size_in_bits`.
+    pub(crate) fn union(&mut self, other: &BitArray) {
+        for (byte, other_byte) in self.array.iter_mut().zip(other.array.iter()) {
+            *byte |= *other_byte;
+        }
     }
+
+    /// Clear every bit in `self` that is not also set in `other`, i.e. a bitwise AND.
+    ///
+    /// The caller must ensure both bit arrays have the same `size_in_bits`.
+    pub(crate) fn intersection(&mut self, other: &BitArray) {
+        for (byte, other_byte) in self.array.iter_mut().zip(other.array.iter()) {
+            *byte &= *other_byte;
+        }
+    }
+
+    /// The number of bits currently set to 1.
+    pub(crate) fn count_set_bits(&self) -> usize {
+        self.array.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+}
+
+/// The number of bytes needed to back a bit array of `size_in_bits` bits, rounding up to the
+/// nearest whole byte.
+///
+/// Panics if `size_in_bits` is so large the byte count would overflow `usize`. Callers fed
+/// untrusted sizes (e.g. [`BitArray::from_bytes`]) must use [`checked_size_in_bytes`] instead.
+fn size_in_bytes(size_in_bits: usize) -> usize {
+    checked_size_in_bytes(size_in_bits)
+        .expect("size_in_bits is too large to compute a byte length for")
+}
+
+/// Fallible version of [`size_in_bytes`] that returns `None` instead of overflowing when
+/// `size_in_bits` is too large for the rounded-up byte count to fit in a `usize`.
+fn checked_size_in_bytes(size_in_bits: usize) -> Option<usize> {
+    size_in_bits.checked_add(7).map(|rounded| rounded / 8)
+}
+
+/// Map a hash function output uniformly onto `[0, size)`.
+///
+/// Shared by [`BitArray`] and the counter array backing `CountingBloomFilter`, since both need to
+/// turn a 64-bit hash into an index into a fixed-size array.
+///
+/// Uses Lemire's integer multiply-shift reduction rather than scaling through `f64`: the hash is
+/// widened to 128 bits, multiplied by `size`, and the top 64 bits of the product are kept. This
+/// avoids float conversions and, unlike dividing by `u64::MAX` and truncating, distributes every
+/// input uniformly across the full range instead of biasing towards the low end and the extremes.
+pub(crate) fn index_from_u64(i: u64, size: usize) -> usize {
+    ((i as u128 * size as u128) >> 64) as usize
 }
 
 #[cfg(test)]
@@ -103,4 +177,53 @@ mod tests {
         assert_eq!(ba.get_bit(2), false);
         assert_eq!(ba.get_bit(3), false);
     }
+
+    #[test]
+    fn union_sets_bits_from_either_array() {
+        let size = 8 * 4;
+        let mut a = BitArray::new(size);
+        let mut b = BitArray::new(size);
+
+        a.set_bit(0);
+        b.set_bit(1);
+
+        a.union(&b);
+
+        assert_eq!(a.get_bit(0), true);
+        assert_eq!(a.get_bit(1), true);
+        assert_eq!(a.get_bit(2), false);
+    }
+
+    #[test]
+    fn count_set_bits_counts_bits_across_all_bytes() {
+        let size = 8 * 4 + 3;
+        let mut ba = BitArray::new(size);
+
+        assert_eq!(ba.count_set_bits(), 0);
+
+        ba.set_bit(0);
+        ba.set_bit(9);
+        ba.set_bit(size - 1);
+        assert_eq!(ba.count_set_bits(), 3);
+
+        // Setting an already-set bit does not inflate the count.
+        ba.set_bit(0);
+        assert_eq!(ba.count_set_bits(), 3);
+    }
+
+    #[test]
+    fn intersection_keeps_only_bits_set_in_both_arrays() {
+        let size = 8 * 4;
+        let mut a = BitArray::new(size);
+        let mut b = BitArray::new(size);
+
+        a.set_bit(0);
+        a.set_bit(1);
+        b.set_bit(1);
+
+        a.intersection(&b);
+
+        assert_eq!(a.get_bit(0), false);
+        assert_eq!(a.get_bit(1), true);
+    }
 }
@@ -0,0 +1,223 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasherDefault, Hash};
+
+use crate::bit_array::index_from_u64;
+use crate::{hash_positions, size_and_num_hash_functions};
+
+/// A counter slot used by [`CountingBloomFilter`].
+///
+/// Implemented for `u8` and `u16` so callers can pick the counter width that matches how many
+/// times they expect a single slot to be incremented before it would otherwise wrap around.
+pub trait CounterStorage: Copy {
+    /// The value a freshly allocated counter starts at.
+    const ZERO: Self;
+
+    /// The largest value a counter can hold.
+    ///
+    /// Counters saturate at this value rather than wrapping, so that an overflowed slot can only
+    /// ever cause extra false positives, never a false negative from wrapping back to zero.
+    const MAX: Self;
+
+    /// Increment the counter, saturating at [`CounterStorage::MAX`].
+    fn increment(self) -> Self;
+
+    /// Decrement the counter, saturating at [`CounterStorage::ZERO`].
+    ///
+    /// A counter at [`CounterStorage::MAX`] may have overflowed, so it is left unchanged.
+    fn decrement(self) -> Self;
+
+    /// Whether the counter is currently zero.
+    fn is_zero(self) -> bool;
+}
+
+impl CounterStorage for u8 {
+    const ZERO: u8 = 0;
+    const MAX: u8 = u8::MAX;
+
+    fn increment(self) -> u8 {
+        self.saturating_add(1)
+    }
+
+    fn decrement(self) -> u8 {
+        if self == u8::MAX {
+            return self;
+        }
+        self.saturating_sub(1)
+    }
+
+    fn is_zero(self) -> bool {
+        self == 0
+    }
+}
+
+impl CounterStorage for u16 {
+    const ZERO: u16 = 0;
+    const MAX: u16 = u16::MAX;
+
+    fn increment(self) -> u16 {
+        self.saturating_add(1)
+    }
+
+    fn decrement(self) -> u16 {
+        if self == u16::MAX {
+            return self;
+        }
+        self.saturating_sub(1)
+    }
+
+    fn is_zero(self) -> bool {
+        self == 0
+    }
+}
+
+/// A Bloom filter that supports removing elements.
+///
+/// A [`BloomFilter`](crate::BloomFilter) packs one bit per hashed position, so once a bit is set
+/// there is no way to tell which insertion set it, and clearing it on removal could silently
+/// clear bits that are still needed by another element. `CountingBloomFilter` instead keeps a
+/// small saturating counter at each position: `insert` increments the `k` hashed counters and
+/// `remove` decrements the same counters, so a position only reads as unset once nothing inserted
+/// still depends on it.
+///
+/// The counter width is chosen with the `C` type parameter, typically `u8` or `u16` depending on
+/// how many overlapping insertions a single slot is expected to see.
+pub struct CountingBloomFilter<C: CounterStorage = u8> {
+    counters: Vec<C>,
+    num_hash_functions: usize,
+    build_hasher: BuildHasherDefault<DefaultHasher>,
+}
+
+impl<C: CounterStorage> CountingBloomFilter<C> {
+    /// Construct a counting Bloom filter.
+    ///
+    /// Explicitly giving the number of counters to use, and the number of different hash
+    /// functions.
+    pub fn new(size: usize, num_hash_functions: usize) -> CountingBloomFilter<C> {
+        CountingBloomFilter {
+            counters: vec![C::ZERO; size],
+            num_hash_functions,
+            build_hasher: BuildHasherDefault::default(),
+        }
+    }
+
+    /// Construct a counting Bloom filter with the given upper bound for the false positive
+    /// probability.
+    ///
+    /// Where the upper bound is valid as long as no more than the given maximum number of
+    /// elements are inserted at once into the filter (elements that have been inserted and then
+    /// removed again do not count). Uses the same sizing as
+    /// [`BloomFilter::with_false_positive_bound`](crate::BloomFilter::with_false_positive_bound).
+    pub fn with_false_positive_bound(
+        false_positive_probability: f32,
+        max_insertions: u32,
+    ) -> CountingBloomFilter<C> {
+        let (size, num_hash_functions) =
+            size_and_num_hash_functions(false_positive_probability, max_insertions);
+
+        CountingBloomFilter::new(size, num_hash_functions)
+    }
+
+    /// Insert an element into the Bloom filter.
+    pub fn insert<T: Hash>(&mut self, value: T) {
+        for position in hash_positions(value, self.num_hash_functions, &self.build_hasher) {
+            let index = self.index_from_u64(position);
+            self.counters[index] = self.counters[index].increment();
+        }
+    }
+
+    /// Remove an element from the Bloom filter.
+    ///
+    /// This must only be called with a value that was previously inserted (and not already
+    /// removed), otherwise it may decrement counters that other, still-present elements depend
+    /// on, turning them into false negatives.
+    pub fn remove<T: Hash>(&mut self, value: T) {
+        for position in hash_positions(value, self.num_hash_functions, &self.build_hasher) {
+            let index = self.index_from_u64(position);
+            self.counters[index] = self.counters[index].decrement();
+        }
+    }
+
+    /// Query for the given value in the Bloom filter.
+    ///
+    /// If this returns `false` the value is guaranteed to not currently be in the set. When it
+    /// returns `true` the value is either in the set, or the counters that would have been
+    /// incremented by inserting the element are all nonzero by chance of other insertions. The
+    /// latter case is called a false positive.
+    pub fn maybe_contains<T: Hash>(&self, value: T) -> bool {
+        for position in hash_positions(value, self.num_hash_functions, &self.build_hasher) {
+            if self.counters[self.index_from_u64(position)].is_zero() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn index_from_u64(&self, i: u64) -> usize {
+        index_from_u64(i, self.counters.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_remove_and_query_single_element() {
+        let mut cbf: CountingBloomFilter<u8> = CountingBloomFilter::new(8 * 4, 1);
+
+        cbf.insert(12);
+        assert_eq!(cbf.maybe_contains(12), true);
+        assert_eq!(cbf.maybe_contains(13), false);
+
+        cbf.remove(12);
+        assert_eq!(cbf.maybe_contains(12), false);
+    }
+
+    #[test]
+    fn remove_of_other_element_does_not_affect_shared_counters() {
+        let mut cbf: CountingBloomFilter<u8> = CountingBloomFilter::new(8 * 4, 4);
+
+        cbf.insert("foo");
+        cbf.insert("bar");
+
+        cbf.remove("bar");
+
+        // "foo" is still in the filter even though "bar" shared some of its counters.
+        assert_eq!(cbf.maybe_contains("foo"), true);
+        assert_eq!(cbf.maybe_contains("bar"), false);
+    }
+
+    #[test]
+    fn counters_saturate_instead_of_wrapping() {
+        let mut cbf: CountingBloomFilter<u8> = CountingBloomFilter::new(8, 1);
+
+        // With a single hash function, every insertion of `0` lands on the same counter, so
+        // inserting it 256 times drives that counter one past `u8::MAX`. Saturating pins it at
+        // 255; wrapping would instead carry it back around to exactly zero.
+        for _ in 0..256 {
+            cbf.insert(0);
+        }
+
+        // A saturated counter stays at 255 on removal, so the element stays in the filter. If
+        // `increment` wrapped instead, the counter would already be back at zero and this would
+        // be a false negative.
+        cbf.remove(0);
+        assert_eq!(cbf.maybe_contains(0), true);
+    }
+
+    #[test]
+    fn with_false_positive_bound() {
+        let mut cbf: CountingBloomFilter<u8> =
+            CountingBloomFilter::with_false_positive_bound(0.01, 1000);
+
+        for i in 0..100 {
+            cbf.insert(i);
+            assert_eq!(cbf.maybe_contains(i), true);
+        }
+
+        // This could fail here due to the bloom filter giving a false positive, but the
+        // probability is less than 1%.
+        assert_eq!(cbf.maybe_contains(100), false);
+    }
+}
@@ -1,10 +1,13 @@
 use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
 
 mod bit_array;
+mod counting;
 
 use bit_array::BitArray;
 
+pub use counting::{CounterStorage, CountingBloomFilter};
+
 /// A Bloom filter.
 ///
 /// A Bloom filter is a probabilistic data structure that can be used to represent a set. When
@@ -22,21 +25,27 @@ use bit_array::BitArray;
 /// positive probability.
 ///
 /// [`with_false_positive_bound`]: BloomFilter::with_false_positive_bound
-pub struct BloomFilter {
+///
+/// # Hashing
+///
+/// By default the filter hashes elements with [`DefaultHasher`], which is not the fastest option
+/// but needs no setup. A faster, non-cryptographic hasher can be plugged in with
+/// [`with_hasher`](BloomFilter::with_hasher) instead, which also lets the caller fix a
+/// deterministic seed so that, e.g., filters built on separate nodes hash identically and can be
+/// merged with [`union`](BloomFilter::union).
+pub struct BloomFilter<S = BuildHasherDefault<DefaultHasher>> {
     bit_array: BitArray,
     num_hash_functions: usize,
+    build_hasher: S,
 }
 
-impl BloomFilter {
+impl BloomFilter<BuildHasherDefault<DefaultHasher>> {
     /// Construct a Bloom filter.
     ///
     /// Explicitly giving the size in bits of the underlying bit array, and the number of different
     /// hash functions to use.
     pub fn new(size: usize, num_hash_functions: usize) -> BloomFilter {
-        BloomFilter {
-            bit_array: BitArray::new(size),
-            num_hash_functions,
-        }
+        BloomFilter::with_hasher(size, num_hash_functions, BuildHasherDefault::default())
     }
 
     /// Construct a Bloom filter with the given upper bound for the false positive probability.
@@ -48,33 +57,84 @@ impl BloomFilter {
         false_positive_probability: f32,
         max_insertions: u32,
     ) -> BloomFilter {
-        let multiplier = -false_positive_probability.ln() / 2f32.ln().powf(2.0);
-        let size = (max_insertions as f32 * multiplier).ceil();
-
-        if size > usize::MAX as f32 {
-            panic!(
-                concat!(
-                    "The bit array size required to reach this false positive bound is ",
-                    "larger than the maximum allowed: {}"
-                ),
-                usize::MAX
-            );
+        let (size, num_hash_functions) =
+            size_and_num_hash_functions(false_positive_probability, max_insertions);
+
+        BloomFilter::new(size, num_hash_functions)
+    }
+
+    /// Reconstruct a Bloom filter previously serialized with [`BloomFilter::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<BloomFilter, FromBytesError> {
+        BloomFilter::from_bytes_with_hasher(bytes, BuildHasherDefault::default())
+    }
+}
+
+impl<S: BuildHasher> BloomFilter<S> {
+    /// Construct a Bloom filter that hashes elements with `build_hasher` rather than the default
+    /// [`DefaultHasher`].
+    ///
+    /// Explicitly giving the size in bits of the underlying bit array, and the number of different
+    /// hash functions to use.
+    pub fn with_hasher(size: usize, num_hash_functions: usize, build_hasher: S) -> BloomFilter<S> {
+        BloomFilter {
+            bit_array: BitArray::new(size),
+            num_hash_functions,
+            build_hasher,
         }
+    }
 
-        // No need to check this conversion to `usize` since this value is strictly less than the
-        // size computed above.
-        let num_hash_functions = (-false_positive_probability.log2()).ceil() as usize;
+    /// Construct a Bloom filter that hashes elements with `build_hasher`, sized to reach the
+    /// given upper bound for the false positive probability.
+    ///
+    /// See [`with_false_positive_bound`](BloomFilter::with_false_positive_bound) for details on
+    /// the bound itself.
+    pub fn with_hasher_and_false_positive_bound(
+        false_positive_probability: f32,
+        max_insertions: u32,
+        build_hasher: S,
+    ) -> BloomFilter<S> {
+        let (size, num_hash_functions) =
+            size_and_num_hash_functions(false_positive_probability, max_insertions);
 
-        BloomFilter::new(size as usize, num_hash_functions)
+        BloomFilter::with_hasher(size, num_hash_functions, build_hasher)
+    }
+
+    /// Reconstruct a Bloom filter previously serialized with [`BloomFilter::to_bytes`], hashing
+    /// elements with `build_hasher`.
+    ///
+    /// `build_hasher` must hash values the same way the filter that was serialized did, or
+    /// membership tests against the reconstructed filter will be meaningless.
+    pub fn from_bytes_with_hasher(
+        bytes: &[u8],
+        build_hasher: S,
+    ) -> Result<BloomFilter<S>, FromBytesError> {
+        let header_size = 1 + 8 + 8;
+
+        if bytes.len() < header_size {
+            return Err(FromBytesError::Truncated);
+        }
+
+        let version = bytes[0];
+        if version != SERIALIZATION_FORMAT_VERSION {
+            return Err(FromBytesError::UnsupportedVersion(version));
+        }
+
+        let size_in_bits = u64::from_le_bytes(bytes[1..9].try_into().unwrap()) as usize;
+        let num_hash_functions = u64::from_le_bytes(bytes[9..17].try_into().unwrap()) as usize;
+
+        let bit_array = BitArray::from_bytes(size_in_bits, &bytes[header_size..])?;
+
+        Ok(BloomFilter {
+            bit_array,
+            num_hash_functions,
+            build_hasher,
+        })
     }
 
     /// Insert an element into the Bloom filter.
     pub fn insert<T: Hash>(&mut self, value: T) {
-        for i in 0..self.num_hash_functions {
-            let mut hasher = DefaultHasher::new();
-            i.hash(&mut hasher);
-            value.hash(&mut hasher);
-            self.bit_array.set_bit_from_u64(hasher.finish());
+        for position in hash_positions(value, self.num_hash_functions, &self.build_hasher) {
+            self.bit_array.set_bit_from_u64(position);
         }
     }
 
@@ -85,18 +145,217 @@ impl BloomFilter {
     /// inserting the element has by chance been by other insertions. The latter case is called a
     /// false positive.
     pub fn maybe_contains<T: Hash>(&self, value: T) -> bool {
-        for i in 0..self.num_hash_functions {
-            let mut hasher = DefaultHasher::new();
-            i.hash(&mut hasher);
-            value.hash(&mut hasher);
-
-            if !self.bit_array.get_bit_from_u64(hasher.finish()) {
+        for position in hash_positions(value, self.num_hash_functions, &self.build_hasher) {
+            if !self.bit_array.get_bit_from_u64(position) {
                 return false;
             }
         }
 
         true
     }
+
+    /// Combine this Bloom filter with `other` into the union of the two, in place.
+    ///
+    /// The union represents exactly the union of the two sets that were inserted into `self` and
+    /// `other`: `self.maybe_contains(x)` after this call returns the same answer as
+    /// `self.maybe_contains(x) || other.maybe_contains(x)` would have before it, modulo false
+    /// positives. This supports e.g. distributed workflows where partial filters are built on
+    /// separate nodes and then merged.
+    ///
+    /// Returns an error if `self` and `other` do not have the same size and number of hash
+    /// functions, since bits would not otherwise line up between the two. The two filters must
+    /// also have been built with hashers that hash identically (e.g. the same seed), or the merged
+    /// filter's membership tests will be meaningless.
+    pub fn union(&mut self, other: &BloomFilter<S>) -> Result<(), IncompatibleBloomFilterError> {
+        self.check_compatible(other)?;
+        self.bit_array.union(&other.bit_array);
+        Ok(())
+    }
+
+    /// Combine this Bloom filter with `other` into the intersection of the two, in place.
+    ///
+    /// Unlike [`union`](BloomFilter::union), the result is not exact: it is guaranteed to contain
+    /// the true intersection of the two inserted sets, but may also contain elements that were
+    /// only inserted into one of the two filters, as a false positive.
+    ///
+    /// Returns an error if `self` and `other` do not have the same size and number of hash
+    /// functions, since bits would not otherwise line up between the two.
+    pub fn intersection(
+        &mut self,
+        other: &BloomFilter<S>,
+    ) -> Result<(), IncompatibleBloomFilterError> {
+        self.check_compatible(other)?;
+        self.bit_array.intersection(&other.bit_array);
+        Ok(())
+    }
+
+    fn check_compatible(&self, other: &BloomFilter<S>) -> Result<(), IncompatibleBloomFilterError> {
+        if self.bit_array.size_in_bits() != other.bit_array.size_in_bits()
+            || self.num_hash_functions != other.num_hash_functions
+        {
+            return Err(IncompatibleBloomFilterError);
+        }
+
+        Ok(())
+    }
+
+    /// Serialize this Bloom filter into a compact byte buffer.
+    ///
+    /// The buffer can later be turned back into an equivalent Bloom filter with
+    /// [`BloomFilter::from_bytes`] (or [`from_bytes_with_hasher`](BloomFilter::from_bytes_with_hasher)
+    /// for a non-default hasher). This is useful for e.g. caching a filter to disk, or shipping a
+    /// prebuilt filter to clients. The hasher itself is not part of the serialized bytes; the
+    /// caller is responsible for reconstructing the filter with the same hasher it was built with.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + 8 + 8 + self.bit_array.as_bytes().len());
+
+        bytes.push(SERIALIZATION_FORMAT_VERSION);
+        bytes.extend_from_slice(&(self.bit_array.size_in_bits() as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.num_hash_functions as u64).to_le_bytes());
+        bytes.extend_from_slice(self.bit_array.as_bytes());
+
+        bytes
+    }
+
+    /// Estimate how many distinct elements have been inserted into this Bloom filter.
+    ///
+    /// Uses the standard count estimator for a Bloom filter with `m` bits, `k` hash functions and
+    /// `X` bits currently set: `-(m/k) * ln(1 - X/m)`. Like [`maybe_contains`](BloomFilter::maybe_contains),
+    /// this is an estimate, not an exact count: it assumes the inserted elements were distinct and
+    /// hashed uniformly at random.
+    pub fn estimated_len(&self) -> usize {
+        let m = self.bit_array.size_in_bits() as f64;
+        let k = self.num_hash_functions as f64;
+        let x = self.bit_array.count_set_bits() as f64;
+
+        (-(m / k) * (1.0 - x / m).ln()).round() as usize
+    }
+
+    /// The fraction of bits in the underlying bit array that are currently set.
+    pub fn fill_ratio(&self) -> f64 {
+        self.bit_array.count_set_bits() as f64 / self.bit_array.size_in_bits() as f64
+    }
+
+    /// Estimate the current false positive probability of this Bloom filter, given how full it
+    /// currently is.
+    ///
+    /// This is computed as `fill_ratio().powi(num_hash_functions)`, and grows as more elements are
+    /// inserted. Unlike the bound passed to [`with_false_positive_bound`](BloomFilter::with_false_positive_bound),
+    /// this reflects the filter's actual current state, so it can be used to detect when a filter
+    /// has saturated past its designed bound and should be rebuilt or grown.
+    pub fn current_false_positive_rate(&self) -> f64 {
+        self.fill_ratio().powi(self.num_hash_functions as i32)
+    }
+}
+
+/// The version of the on-disk format written by [`BloomFilter::to_bytes`].
+///
+/// Bumped whenever the layout changes, so that [`BloomFilter::from_bytes`] can reject buffers it
+/// no longer knows how to read instead of misinterpreting them.
+const SERIALIZATION_FORMAT_VERSION: u8 = 1;
+
+/// Error returned when [`BloomFilter::from_bytes`] is given a buffer it cannot reconstruct a
+/// Bloom filter from.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FromBytesError {
+    /// The buffer is shorter than the header, or its length after the header is not exactly what
+    /// the bit array it declares requires.
+    Truncated,
+    /// The buffer declares a format version this version of the crate does not know how to read.
+    UnsupportedVersion(u8),
+}
+
+impl std::fmt::Display for FromBytesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FromBytesError::Truncated => {
+                write!(f, "byte buffer has the wrong length to be a bloom filter")
+            }
+            FromBytesError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported bloom filter serialization version: {}",
+                    version
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FromBytesError {}
+
+/// Error returned when combining two [`BloomFilter`]s that do not have the same size and number
+/// of hash functions.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IncompatibleBloomFilterError;
+
+impl std::fmt::Display for IncompatibleBloomFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bloom filters must have the same size and number of hash functions to be combined"
+        )
+    }
+}
+
+impl std::error::Error for IncompatibleBloomFilterError {}
+
+/// Compute the bit array size and number of hash functions needed to reach a given upper bound
+/// on the false positive probability, for at most `max_insertions` elements.
+///
+/// Shared by [`BloomFilter::with_false_positive_bound`] and
+/// [`CountingBloomFilter::with_false_positive_bound`]. Upper bound taken from
+/// https://en.wikipedia.org/wiki/Bloom_filter.
+pub(crate) fn size_and_num_hash_functions(
+    false_positive_probability: f32,
+    max_insertions: u32,
+) -> (usize, usize) {
+    let multiplier = -false_positive_probability.ln() / 2f32.ln().powf(2.0);
+    let size = (max_insertions as f32 * multiplier).ceil();
+
+    if size > usize::MAX as f32 {
+        panic!(
+            concat!(
+                "The bit array size required to reach this false positive bound is ",
+                "larger than the maximum allowed: {}"
+            ),
+            usize::MAX
+        );
+    }
+
+    // No need to check this conversion to `usize` since this value is strictly less than the
+    // size computed above.
+    let num_hash_functions = (-false_positive_probability.log2()).ceil() as usize;
+
+    (size as usize, num_hash_functions)
+}
+
+/// Derive the `num_hash_functions` bit positions for `value` using Kirsch–Mitzenmacher double
+/// hashing, hashing with `build_hasher`.
+///
+/// Rather than hashing `value` once per hash function (which is wasteful for large values or a
+/// large `num_hash_functions`), `value` is hashed exactly twice to get two independent digests
+/// `h1` and `h2`, and the i-th position is then derived as `h1 + i * h2`. This preserves the same
+/// asymptotic false positive rate as using `num_hash_functions` independent hash functions, while
+/// making insertion and lookup cost `O(size_of(value) + num_hash_functions)` instead of
+/// `O(num_hash_functions * size_of(value))`.
+pub(crate) fn hash_positions<T: Hash, S: BuildHasher>(
+    value: T,
+    num_hash_functions: usize,
+    build_hasher: &S,
+) -> impl Iterator<Item = u64> {
+    let mut first_hasher = build_hasher.build_hasher();
+    0u8.hash(&mut first_hasher);
+    value.hash(&mut first_hasher);
+    let h1 = first_hasher.finish();
+
+    let mut second_hasher = build_hasher.build_hasher();
+    1u8.hash(&mut second_hasher);
+    value.hash(&mut second_hasher);
+    // If h2 is 0 every position collapses to h1, so force it to be nonzero.
+    let h2 = second_hasher.finish() | 1;
+
+    (0..num_hash_functions).map(move |i| h1.wrapping_add((i as u64).wrapping_mul(h2)))
 }
 
 #[cfg(test)]
@@ -133,4 +392,140 @@ mod tests {
         // probability is less than 1%.
         assert_eq!(bf.maybe_contains(100), false);
     }
+
+    #[test]
+    fn union_contains_elements_from_both_filters() {
+        let mut a = BloomFilter::new(8 * 4, 4);
+        let mut b = BloomFilter::new(8 * 4, 4);
+
+        a.insert("foo");
+        b.insert("bar");
+
+        a.union(&b).unwrap();
+
+        assert_eq!(a.maybe_contains("foo"), true);
+        assert_eq!(a.maybe_contains("bar"), true);
+    }
+
+    #[test]
+    fn intersection_contains_only_shared_elements() {
+        let mut a = BloomFilter::new(8 * 4, 4);
+        let mut b = BloomFilter::new(8 * 4, 4);
+
+        a.insert("foo");
+        a.insert("shared");
+        b.insert("bar");
+        b.insert("shared");
+
+        a.intersection(&b).unwrap();
+
+        assert_eq!(a.maybe_contains("shared"), true);
+        assert_eq!(a.maybe_contains("foo"), false);
+    }
+
+    #[test]
+    fn union_of_incompatible_filters_is_an_error() {
+        let mut a = BloomFilter::new(8 * 4, 4);
+        let b = BloomFilter::new(8 * 8, 4);
+
+        assert_eq!(a.union(&b), Err(IncompatibleBloomFilterError));
+    }
+
+    #[test]
+    fn round_trip_through_bytes_gives_identical_membership_answers() {
+        let mut bf = BloomFilter::with_false_positive_bound(0.01, 1000);
+
+        for i in 0..100 {
+            bf.insert(i);
+        }
+
+        let restored = BloomFilter::from_bytes(&bf.to_bytes()).unwrap();
+
+        for i in 0..100 {
+            assert_eq!(restored.maybe_contains(i), bf.maybe_contains(i));
+        }
+        assert_eq!(restored.maybe_contains(100), bf.maybe_contains(100));
+    }
+
+    #[test]
+    fn from_bytes_rejects_truncated_buffer() {
+        assert!(matches!(
+            BloomFilter::from_bytes(&[1, 2, 3]),
+            Err(FromBytesError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn from_bytes_rejects_size_in_bits_too_large_for_the_buffer() {
+        let mut bytes = vec![SERIALIZATION_FORMAT_VERSION];
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        bytes.extend_from_slice(&1u64.to_le_bytes());
+
+        assert!(matches!(
+            BloomFilter::from_bytes(&bytes),
+            Err(FromBytesError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn from_bytes_rejects_unsupported_version() {
+        let mut bytes = BloomFilter::new(8, 1).to_bytes();
+        bytes[0] = 255;
+
+        assert!(matches!(
+            BloomFilter::from_bytes(&bytes),
+            Err(FromBytesError::UnsupportedVersion(255))
+        ));
+    }
+
+    #[test]
+    fn with_hasher_uses_the_given_hasher_to_insert_and_query() {
+        use std::collections::hash_map::RandomState;
+
+        let build_hasher = RandomState::new();
+        let mut bf = BloomFilter::with_hasher(8 * 4, 4, build_hasher.clone());
+
+        bf.insert("foo");
+        assert_eq!(bf.maybe_contains("foo"), true);
+        assert_eq!(bf.maybe_contains("bar"), false);
+
+        // A filter built with a clone of the same hasher must round trip through bytes.
+        let restored =
+            BloomFilter::from_bytes_with_hasher(&bf.to_bytes(), build_hasher).unwrap();
+        assert_eq!(restored.maybe_contains("foo"), true);
+    }
+
+    #[test]
+    fn estimated_len_is_close_to_the_number_of_inserted_elements() {
+        let mut bf = BloomFilter::with_false_positive_bound(0.01, 1000);
+
+        for i in 0..500 {
+            bf.insert(i);
+        }
+
+        let estimate = bf.estimated_len();
+        assert!(
+            (400..=600).contains(&estimate),
+            "expected estimate close to 500, got {}",
+            estimate
+        );
+    }
+
+    #[test]
+    fn fill_ratio_and_false_positive_rate_grow_as_elements_are_inserted() {
+        let mut bf = BloomFilter::new(8 * 32, 4);
+        assert_eq!(bf.fill_ratio(), 0.0);
+        assert_eq!(bf.current_false_positive_rate(), 0.0);
+
+        for i in 0..100 {
+            bf.insert(i);
+        }
+
+        assert!(bf.fill_ratio() > 0.0);
+        assert!(bf.current_false_positive_rate() > 0.0);
+        assert_eq!(
+            bf.current_false_positive_rate(),
+            bf.fill_ratio().powi(4)
+        );
+    }
 }